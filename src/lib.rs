@@ -26,12 +26,67 @@
 extern crate log;
 
 extern crate crossbeam;
+extern crate jobserver;
+extern crate num_cpus;
 extern crate syncbox;
 
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
 use crossbeam::scope;
 use crossbeam::sync::MsQueue;
 use syncbox::LinkedQueue;
 
+/// Tracks the next sequence number a reordering consumer is waiting to emit, and lets a feeder
+/// block on it without busy-spinning until it has advanced far enough to free up room in the
+/// reorder window.
+struct Watermark {
+    next_to_emit: Mutex<usize>,
+    advanced: Condvar,
+}
+
+impl Watermark {
+    fn new() -> Watermark {
+        Watermark {
+            next_to_emit: Mutex::new(0),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Called by the consumer once it has emitted up through `next_to_emit`; wakes any feeder
+    /// blocked in `wait_until_within`.
+    fn advance_to(&self, next_to_emit: usize) {
+        *self.next_to_emit.lock().unwrap() = next_to_emit;
+        self.advanced.notify_all();
+    }
+
+    /// Block until `seq` is within `window` of the last value passed to `advance_to`.
+    fn wait_until_within(&self, seq: usize, window: usize) {
+        let mut next_to_emit = self.next_to_emit.lock().unwrap();
+        while seq.saturating_sub(*next_to_emit) > window {
+            next_to_emit = self.advanced.wait(next_to_emit).unwrap();
+        }
+    }
+
+    /// Like `wait_until_within`, but also wakes up and returns early once `cancelled` is set, so
+    /// a feeder blocked here doesn't keep a cancelled run from winding down.
+    fn wait_until_within_or_cancelled(&self, seq: usize, window: usize, cancelled: &AtomicBool) {
+        let mut next_to_emit = self.next_to_emit.lock().unwrap();
+        while seq.saturating_sub(*next_to_emit) > window && !cancelled.load(Ordering::Acquire) {
+            next_to_emit = self.advanced.wait(next_to_emit).unwrap();
+        }
+    }
+
+    /// Wake any feeder blocked in `wait_until_within`/`wait_until_within_or_cancelled` without
+    /// advancing the watermark itself — used to rouse a feeder when a run is cancelled.
+    fn notify(&self) {
+        let _next_to_emit = self.next_to_emit.lock().unwrap();
+        self.advanced.notify_all();
+    }
+}
+
 pub fn pipeline<Q, R, QF, JF, W>(name: &str,
                                  num_workers: usize,
                                  work: W,
@@ -96,6 +151,949 @@ pub fn pipeline<Q, R, QF, JF, W>(name: &str,
     });
 }
 
+/// Like [`pipeline`](fn.pipeline.html), but guarantees that `joiner` sees results in the same
+/// order as `work` yielded the corresponding items, rather than worker-completion order.
+///
+/// Each work item is tagged with a sequence number before it's handed to a worker; the result
+/// consumer buffers out-of-order results in a reorder buffer and only calls `joiner` once the
+/// next expected sequence number is available, draining any runs that are already buffered. The
+/// feeder blocks once the distance between the next item it would feed and the next item the
+/// consumer is waiting to emit exceeds the reorder window — `num_workers * 20` by default, or a
+/// value of your choosing via [`ordered_pipeline_with_capacity`](fn.ordered_pipeline_with_capacity.html)
+/// — so memory use stays bounded even if one work item takes far longer than the rest.
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use cue::ordered_pipeline;
+///
+/// let mut results = Vec::new();
+///
+/// ordered_pipeline("ordered123",
+///                   4,
+///                   (0..1_000),
+///                   |n| n * 5,
+///                   |r| results.push(r));
+///
+/// for i in 0..1_000 {
+///     assert_eq!(i * 5, results[i]);
+/// }
+/// ```
+pub fn ordered_pipeline<Q, R, QF, JF, W>(name: &str,
+                                         num_workers: usize,
+                                         work: W,
+                                         worker: QF,
+                                         joiner: JF)
+    where Q: Send + Sized,
+          R: Send + Sized,
+          QF: Fn(Q) -> R + Sync,
+          JF: FnMut(R) + Send + Sync,
+          W: Iterator<Item = Q>
+{
+    ordered_pipeline_with_capacity(name, num_workers, num_workers * 20, work, worker, joiner)
+}
+
+/// Like [`ordered_pipeline`](fn.ordered_pipeline.html), but lets the caller configure the
+/// reorder window — how far ahead of the next result the consumer is waiting on, the feeder is
+/// allowed to get — instead of always using the `num_workers * 20` default.
+pub fn ordered_pipeline_with_capacity<Q, R, QF, JF, W>(name: &str,
+                                                        num_workers: usize,
+                                                        window: usize,
+                                                        work: W,
+                                                        worker: QF,
+                                                        mut joiner: JF)
+    where Q: Send + Sized,
+          R: Send + Sized,
+          QF: Fn(Q) -> R + Sync,
+          JF: FnMut(R) + Send + Sync,
+          W: Iterator<Item = Q>
+{
+    let results = MsQueue::<Option<(usize, R)>>::new();
+    let queries = LinkedQueue::<Option<(usize, Q)>>::with_capacity(num_workers * 20);
+    let next_to_emit = Watermark::new();
+
+    scope(|scope| {
+        // results consumer, reordering as it goes
+        scope.spawn(|| {
+            let mut num_ended = 0;
+            let mut num_processed = 0;
+            let mut next = 0;
+            let mut reorder_buffer = BTreeMap::new();
+
+            // while there are still workers which haven't signalled termination
+            while num_ended < num_workers {
+
+                match results.pop() {
+                    // the worker has produced some result, possibly out of order
+                    Some((seq, result)) => {
+                        if seq == next {
+                            joiner(result);
+                            next += 1;
+
+                            // drain any already-buffered results that are now in order
+                            while let Some(buffered) = reorder_buffer.remove(&next) {
+                                joiner(buffered);
+                                next += 1;
+                            }
+
+                            next_to_emit.advance_to(next);
+                        } else {
+                            reorder_buffer.insert(seq, result);
+                        }
+
+                        num_processed += 1;
+                        log(name, num_processed);
+                    }
+                    // the worker has terminated
+                    None => num_ended += 1,
+                }
+            }
+        });
+
+        // workers
+        for _ in 0..num_workers {
+            scope.spawn(|| {
+                // while there's work to be done
+                while let Some((seq, query)) = queries.take() {
+                    // do the work and put the tagged result on the queue
+                    let result = worker(query);
+                    results.push(Some((seq, result)));
+                }
+                // no more work, signal to result thread that i'm exiting
+                results.push(None);
+            });
+        }
+
+        // put work on the queue from the iterator, tagging it with a sequence number
+        for (seq, query) in work.enumerate() {
+            // bound how far ahead of the consumer the feeder is allowed to get, blocking
+            // instead of busy-spinning until the consumer has caught up
+            next_to_emit.wait_until_within(seq, window);
+            queries.put(Some((seq, query)));
+        }
+
+        // tell all the workers there's no more work left
+        for _ in 0..num_workers {
+            queries.put(None);
+        }
+    });
+}
+
+/// Which error a fallible worker reported should stop new work from being started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Run every work item to completion regardless of earlier errors, then return them all.
+    Collect,
+    /// Stop handing queries to `worker` as soon as the first error is seen.
+    AbortOnFirstError,
+}
+
+/// Like [`pipeline`](fn.pipeline.html), but `worker` returns a `Result<R, E>` instead of a bare
+/// `R`. `joiner` only runs on the `Ok` results; any `Err`s are returned to the caller instead of
+/// being silently dropped or left to unwind a panic.
+///
+/// With `FailureMode::Collect`, every work item still runs and all errors are gathered into the
+/// returned `Vec<E>`. With `FailureMode::AbortOnFirstError`, the first `Err` trips a shared abort
+/// flag that workers check on every loop iteration; once tripped they drain and drop the
+/// remaining queries without running `worker` on them, so the pipeline winds down promptly
+/// instead of finishing work that's no longer wanted.
+///
+/// Returns `Ok(())` if no worker reported an error.
+///
+/// ```
+/// use cue::{try_pipeline, FailureMode};
+///
+/// let mut results = Vec::new();
+///
+/// let outcome = try_pipeline("fallible123",
+///                             4,
+///                             (0..100),
+///                             |n| if n == 42 { Err("boom") } else { Ok(n) },
+///                             |r| results.push(r),
+///                             FailureMode::Collect);
+///
+/// assert_eq!(Err(vec!["boom"]), outcome);
+/// ```
+pub fn try_pipeline<Q, R, E, QF, JF, W>(name: &str,
+                                        num_workers: usize,
+                                        work: W,
+                                        worker: QF,
+                                        mut joiner: JF,
+                                        mode: FailureMode)
+                                        -> Result<(), Vec<E>>
+    where Q: Send + Sized,
+          R: Send + Sized,
+          E: Send + Sized,
+          QF: Fn(Q) -> Result<R, E> + Sync,
+          JF: FnMut(R) + Send + Sync,
+          W: Iterator<Item = Q>
+{
+    let results = MsQueue::<Option<Result<R, E>>>::new();
+    let queries = LinkedQueue::<Option<Q>>::with_capacity(num_workers * 20);
+    let abort = AtomicBool::new(false);
+
+    let errors = scope(|scope| {
+        // results consumer
+        let consumer = scope.spawn(|| {
+            let mut num_ended = 0;
+            let mut num_processed = 0;
+            let mut errors = Vec::new();
+
+            // while there are still workers which haven't signalled termination
+            while num_ended < num_workers {
+
+                match results.pop() {
+                    // the worker produced a result
+                    Some(Ok(result)) => {
+                        joiner(result);
+
+                        num_processed += 1;
+                        log(name, num_processed);
+                    }
+                    // the worker hit an error
+                    Some(Err(err)) => {
+                        errors.push(err);
+
+                        if mode == FailureMode::AbortOnFirstError {
+                            abort.store(true, Ordering::Release);
+                        }
+                    }
+                    // the worker has terminated
+                    None => num_ended += 1,
+                }
+            }
+
+            errors
+        });
+
+        // workers
+        for _ in 0..num_workers {
+            scope.spawn(|| {
+                // while there's work to be done
+                while let Some(query) = queries.take() {
+                    // once aborted, drain and drop the remaining queries instead of working them
+                    if abort.load(Ordering::Acquire) {
+                        continue;
+                    }
+
+                    match worker(query) {
+                        Ok(result) => results.push(Some(Ok(result))),
+                        Err(err) => results.push(Some(Err(err))),
+                    }
+                }
+                // no more work, signal to result thread that i'm exiting
+                results.push(None);
+            });
+        }
+
+        // put work on the queue from the iterator, stopping early if a worker has aborted
+        for query in work {
+            if abort.load(Ordering::Acquire) {
+                break;
+            }
+
+            // note that this blocks if the buffer is full
+            queries.put(Some(query));
+        }
+
+        // tell all the workers there's no more work left
+        for _ in 0..num_workers {
+            queries.put(None);
+        }
+
+        consumer.join()
+    });
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Returns the process-wide GNU Make jobserver client, if one was inherited from the environment,
+/// creating it the first time this is called and cloning it on every call after that.
+///
+/// `jobserver::Client::from_env()` inherits file descriptors from the parent `cargo`/`make`
+/// invocation, and its safety contract requires calling it at most once per process and sharing
+/// the resulting `Client` from then on — two independently-constructed `Client`s can end up
+/// racing over the same inherited pipe. Routing every `PipelineConfig::jobserver()` call through
+/// this single lazily-initialized slot is what makes it sound to call `.jobserver()` on more than
+/// one `PipelineConfig` in the same process.
+fn shared_jobserver_client() -> Option<jobserver::Client> {
+    static CLIENT: OnceLock<Option<jobserver::Client>> = OnceLock::new();
+    CLIENT.get_or_init(|| unsafe { jobserver::Client::from_env() }).clone()
+}
+
+/// Bounds an adaptive `PipelineConfig`'s in-flight window, the number of queries allowed to be
+/// fed to workers ahead of the consumer, between `min_window` and `max_window`.
+struct AdaptiveWindow {
+    min_window: usize,
+    max_window: usize,
+}
+
+/// Tracks how many queries have been fed to workers versus finished by the consumer, and lets
+/// the feeder block on that gap instead of busy-spinning while the window shrinks and grows.
+struct AdaptiveGate {
+    state: Mutex<AdaptiveGateState>,
+    changed: Condvar,
+}
+
+struct AdaptiveGateState {
+    num_fed: usize,
+    num_done: usize,
+    window: usize,
+}
+
+impl AdaptiveGate {
+    fn new(window: usize) -> AdaptiveGate {
+        AdaptiveGate {
+            state: Mutex::new(AdaptiveGateState {
+                num_fed: 0,
+                num_done: 0,
+                window,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Called by the feeder before putting each query on the queue: blocks while it's more than
+    /// `window` ahead of the consumer, shrinking the window a notch first since the consumer is
+    /// starving relative to how much is already in flight.
+    fn wait_for_room(&self, min_window: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.num_fed - state.num_done > state.window {
+            if state.window > min_window {
+                state.window -= 1;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Called by the feeder once a query has actually been put on the queue.
+    fn mark_fed(&self) {
+        self.state.lock().unwrap().num_fed += 1;
+    }
+
+    /// Called by the consumer after handling each result: grows the window a notch so workers
+    /// don't run dry waiting on the feeder next time, and wakes a feeder blocked in
+    /// `wait_for_room`.
+    fn mark_done(&self, max_window: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.num_done += 1;
+        if state.window < max_window {
+            state.window += 1;
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// Builds a configured `pipeline` run: worker count, queue sizing, optional adaptive backpressure
+/// tuning, and an optional GNU Make jobserver as a source of execution slots, in place of the
+/// hardcoded `num_workers * 20` that `pipeline` always uses.
+///
+/// ```
+/// use cue::PipelineConfig;
+///
+/// let mut results = Vec::new();
+///
+/// PipelineConfig::auto()
+///     .queue_capacity(256)
+///     .jobserver()
+///     .run("configured123", (0..1_000), |n| n * 5, |r| results.push(r));
+///
+/// assert_eq!(1_000, results.len());
+/// ```
+pub struct PipelineConfig {
+    num_workers: usize,
+    queue_capacity: usize,
+    output_capacity: Option<usize>,
+    adaptive: Option<AdaptiveWindow>,
+    jobserver: Option<jobserver::Client>,
+}
+
+impl PipelineConfig {
+    /// Start from an explicit worker count, with the same `num_workers * 20` queue capacity
+    /// `pipeline` defaults to.
+    pub fn new(num_workers: usize) -> PipelineConfig {
+        PipelineConfig {
+            num_workers,
+            queue_capacity: num_workers * 20,
+            output_capacity: None,
+            adaptive: None,
+            jobserver: None,
+        }
+    }
+
+    /// Start from a worker count picked automatically via `num_cpus::get()`.
+    pub fn auto() -> PipelineConfig {
+        PipelineConfig::new(num_cpus::get())
+    }
+
+    /// Override the input queue's capacity. Defaults to `num_workers * 20`.
+    pub fn queue_capacity(mut self, capacity: usize) -> PipelineConfig {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Give the result queue a capacity of its own instead of sharing the input queue's.
+    pub fn output_capacity(mut self, capacity: usize) -> PipelineConfig {
+        self.output_capacity = Some(capacity);
+        self
+    }
+
+    /// Let the feeder grow or shrink the number of in-flight queries between `min` and `max`,
+    /// based on whether the consumer is keeping up, instead of holding it fixed at
+    /// `queue_capacity`.
+    pub fn adaptive(mut self, min: usize, max: usize) -> PipelineConfig {
+        self.adaptive = Some(AdaptiveWindow {
+            min_window: min,
+            max_window: max,
+        });
+        self
+    }
+
+    /// Have workers acquire an execution slot from a GNU Make jobserver (see the `jobserver`
+    /// crate) before running each query, instead of relying solely on `num_workers`. This lets
+    /// several `cue` pipelines spawned under the same `cargo`/`make` invocation cooperatively
+    /// share a single concurrency budget rather than each oversubscribing the machine with
+    /// `num_workers` CPU-bound threads of their own.
+    ///
+    /// `jobserver::Client::from_env()` inherits file descriptors from that `cargo`/`make`
+    /// invocation and is only sound to call once per process, so this clones a single
+    /// lazily-created client rather than re-inheriting those descriptors every time `jobserver()`
+    /// is called — exactly the case this method exists for.
+    ///
+    /// Falls back to the fixed `num_workers` behavior when no jobserver is found in the
+    /// environment — for example when the pipeline isn't running under `cargo` or `make`.
+    pub fn jobserver(mut self) -> PipelineConfig {
+        self.jobserver = shared_jobserver_client();
+        self
+    }
+
+    /// Run the pipeline with this configuration. Takes the same `work`/`worker`/`joiner`
+    /// arguments as [`pipeline`](fn.pipeline.html).
+    pub fn run<Q, R, QF, JF, W>(&self, name: &str, work: W, worker: QF, mut joiner: JF)
+        where Q: Send + Sized,
+              R: Send + Sized,
+              QF: Fn(Q) -> R + Sync,
+              JF: FnMut(R) + Send + Sync,
+              W: Iterator<Item = Q>
+    {
+        let num_workers = self.num_workers;
+        let queries = LinkedQueue::<Option<Q>>::with_capacity(self.queue_capacity);
+        let results = LinkedQueue::<Option<R>>::with_capacity(self.output_capacity
+            .unwrap_or(self.queue_capacity));
+
+        // in adaptive mode, the feeder holds off while more than `window` queries are in flight
+        // ahead of the consumer; it starts at the floor and is nudged up or down as the run
+        // progresses
+        let gate = self.adaptive.as_ref().map(|adaptive| AdaptiveGate::new(adaptive.min_window));
+
+        scope(|scope| {
+            // results consumer
+            scope.spawn(|| {
+                let mut num_ended = 0;
+                let mut num_processed = 0;
+
+                // while there are still workers which haven't signalled termination
+                while num_ended < num_workers {
+
+                    match results.take() {
+                        // the worker has produced some result
+                        Some(result) => {
+                            joiner(result);
+
+                            num_processed += 1;
+                            log(name, num_processed);
+
+                            // the consumer had something to do; grow the window a notch so
+                            // workers don't run dry waiting on the feeder next time, and wake
+                            // a feeder that might be blocked on the old window
+                            if let (Some(ref gate), Some(ref adaptive)) = (&gate, &self.adaptive) {
+                                gate.mark_done(adaptive.max_window);
+                            }
+                        }
+                        // the worker has terminated
+                        None => num_ended += 1,
+                    }
+                }
+            });
+
+            // workers
+            for _ in 0..num_workers {
+                scope.spawn(|| {
+                    // while there's work to be done
+                    while let Some(query) = queries.take() {
+                        // if a jobserver slot source is configured, hold a token across the
+                        // computation so concurrent `cue` pipelines share one global budget;
+                        // on acquire failure, fall back to running without a token
+                        let _token = self.jobserver.as_ref().and_then(|client| client.acquire().ok());
+
+                        // do the work and put the result on the queue
+                        let result = worker(query);
+                        results.put(Some(result));
+                    }
+                    // no more work, signal to result thread that i'm exiting
+                    results.put(None);
+                });
+            }
+
+            // put work on the queue from the iterator
+            for query in work {
+                if let (Some(ref gate), Some(ref adaptive)) = (&gate, &self.adaptive) {
+                    // block (instead of busy-spinning) until the consumer has caught up enough
+                    // to free room in the window, shrinking it a notch first since the consumer
+                    // is starving relative to how much is already in flight
+                    gate.wait_for_room(adaptive.min_window);
+                }
+
+                // note that this blocks if the buffer is full
+                queries.put(Some(query));
+                if let Some(ref gate) = gate {
+                    gate.mark_fed();
+                }
+            }
+
+            // tell all the workers there's no more work left
+            for _ in 0..num_workers {
+                queries.put(None);
+            }
+        });
+    }
+}
+
+/// A handle to a `pipeline_with_handle` invocation running on its own background thread, letting
+/// another thread request graceful cancellation.
+pub struct PipelineHandle {
+    cancelled: Arc<AtomicBool>,
+    join_handle: thread::JoinHandle<usize>,
+}
+
+impl PipelineHandle {
+    /// Ask the pipeline to stop feeding new queries to workers and wind down. Queries already
+    /// queued up are drained and dropped without being run; nothing new is started.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Block until the pipeline has wound down, joining its crossbeam scope, and return how many
+    /// work items were actually processed before it stopped.
+    pub fn join(self) -> usize {
+        self.join_handle.join().expect("cue pipeline thread panicked")
+    }
+}
+
+/// Like [`pipeline`](fn.pipeline.html), but runs on its own background thread and returns a
+/// [`PipelineHandle`](struct.PipelineHandle.html) immediately instead of blocking the caller
+/// until every work item has been processed.
+///
+/// Calling `handle.cancel()` from any other thread asks the pipeline to stop: the feeder stops
+/// pulling new items from `work`, and workers drain and drop whatever is already queued instead
+/// of running `worker` on it, so the pipeline winds down promptly rather than finishing work
+/// that's no longer wanted. Calling `handle.join()` blocks until that shutdown — or natural
+/// completion — joins the underlying crossbeam scope cleanly, leaving no worker thread running
+/// or detached, and returns the number of items that were processed.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use cue::pipeline_with_handle;
+///
+/// let results = Arc::new(Mutex::new(Vec::new()));
+/// let results_for_pipeline = results.clone();
+///
+/// let handle = pipeline_with_handle("cancellable123",
+///                                    4,
+///                                    (0..1_000),
+///                                    |n| n * 5,
+///                                    move |r| results_for_pipeline.lock().unwrap().push(r));
+///
+/// handle.cancel();
+/// handle.join();
+/// ```
+pub fn pipeline_with_handle<Q, R, QF, JF, W>(name: &str,
+                                             num_workers: usize,
+                                             work: W,
+                                             worker: QF,
+                                             mut joiner: JF)
+                                             -> PipelineHandle
+    where Q: Send + Sized + 'static,
+          R: Send + Sized + 'static,
+          QF: Fn(Q) -> R + Sync + Send + 'static,
+          JF: FnMut(R) + Send + 'static,
+          W: Iterator<Item = Q> + Send + 'static
+{
+    let name = name.to_owned();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancelled_for_thread = cancelled.clone();
+
+    let join_handle = thread::spawn(move || {
+        let cancelled = cancelled_for_thread;
+        let results = MsQueue::<Option<R>>::new();
+        let queries = LinkedQueue::<Option<Q>>::with_capacity(num_workers * 20);
+
+        scope(|scope| {
+            // results consumer
+            let consumer = scope.spawn(|| {
+                let mut num_ended = 0;
+                let mut num_processed = 0;
+
+                // while there are still workers which haven't signalled termination
+                while num_ended < num_workers {
+
+                    match results.pop() {
+                        // the worker has produced some result
+                        Some(result) => {
+                            joiner(result);
+
+                            num_processed += 1;
+                            log(&name, num_processed);
+                        }
+                        // the worker has terminated
+                        None => num_ended += 1,
+                    }
+                }
+
+                num_processed
+            });
+
+            // workers
+            for _ in 0..num_workers {
+                scope.spawn(|| {
+                    // while there's work to be done
+                    while let Some(query) = queries.take() {
+                        // once cancelled, drain and drop the rest so the feeder can finish
+                        // pushing its termination sentinels instead of blocking forever
+                        if cancelled.load(Ordering::Acquire) {
+                            continue;
+                        }
+
+                        let result = worker(query);
+                        results.push(Some(result));
+                    }
+                    // no more work, signal to result thread that i'm exiting
+                    results.push(None);
+                });
+            }
+
+            // put work on the queue from the iterator, stopping early if cancelled
+            for query in work {
+                if cancelled.load(Ordering::Acquire) {
+                    break;
+                }
+
+                // note that this blocks if the buffer is full
+                queries.put(Some(query));
+            }
+
+            // tell all the workers there's no more work left
+            for _ in 0..num_workers {
+                queries.put(None);
+            }
+
+            consumer.join()
+        })
+    });
+
+    PipelineHandle {
+        cancelled,
+        join_handle,
+    }
+}
+
+/// An iterator returned by [`par_map`](fn.par_map.html), yielding results from a pool of
+/// background worker threads in worker-completion order as soon as they're ready.
+///
+/// Dropping the iterator before it's exhausted cancels the run: the feeder thread stops pulling
+/// from `work` and the worker threads drain and drop whatever is already queued, so no thread is
+/// left running once the `ParMap` goes away.
+pub struct ParMap<R> {
+    results: Arc<MsQueue<Option<R>>>,
+    cancelled: Arc<AtomicBool>,
+    num_workers: usize,
+    num_ended: usize,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<R> Iterator for ParMap<R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        while self.num_ended < self.num_workers {
+            match self.results.pop() {
+                Some(result) => return Some(result),
+                None => self.num_ended += 1,
+            }
+        }
+
+        None
+    }
+}
+
+impl<R> Drop for ParMap<R> {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Like [`pipeline`](fn.pipeline.html), but instead of driving a `joiner` closure, returns an
+/// iterator that lazily yields results as they become ready, matching the ergonomics of a
+/// concurrent-map adapter.
+///
+/// Because the returned iterator has to be able to outlive this call, the worker pool runs on
+/// its own owned threads rather than a `crossbeam::scope` — the queues are held behind `Arc`s
+/// shared with those threads instead of being borrowed for the scope's lifetime. Results come
+/// back in worker-completion order; use [`ordered_par_map`](fn.ordered_par_map.html) if you need
+/// them in input order instead.
+///
+/// ```
+/// use cue::par_map;
+///
+/// let mut results: Vec<_> = par_map("parmap123", 4, 0..1_000, |n| n * 5).collect();
+/// results.sort();
+///
+/// for i in 0..1_000 {
+///     assert_eq!(i * 5, results[i]);
+/// }
+/// ```
+pub fn par_map<Q, R, QF, W>(name: &str, num_workers: usize, work: W, worker: QF) -> ParMap<R>
+    where Q: Send + 'static,
+          R: Send + 'static,
+          QF: Fn(Q) -> R + Sync + Send + 'static,
+          W: Iterator<Item = Q> + Send + 'static
+{
+    let name = name.to_owned();
+    let results = Arc::new(MsQueue::<Option<R>>::new());
+    let queries = Arc::new(LinkedQueue::<Option<Q>>::with_capacity(num_workers * 20));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let num_processed = Arc::new(AtomicUsize::new(0));
+    let worker = Arc::new(worker);
+
+    let mut threads = Vec::with_capacity(num_workers + 1);
+
+    // workers
+    for _ in 0..num_workers {
+        let queries = queries.clone();
+        let results = results.clone();
+        let cancelled = cancelled.clone();
+        let num_processed = num_processed.clone();
+        let worker = worker.clone();
+        let name = name.clone();
+
+        threads.push(thread::spawn(move || {
+            // while there's work to be done
+            while let Some(query) = queries.take() {
+                // once cancelled, drain and drop the rest so the feeder can finish
+                if cancelled.load(Ordering::Acquire) {
+                    continue;
+                }
+
+                let result = worker(query);
+                results.push(Some(result));
+
+                log(&name, num_processed.fetch_add(1, Ordering::Relaxed) + 1);
+            }
+            // no more work, signal to the iterator that i'm exiting
+            results.push(None);
+        }));
+    }
+
+    // feeder
+    {
+        let queries = queries.clone();
+        let cancelled = cancelled.clone();
+
+        threads.push(thread::spawn(move || {
+            // put work on the queue from the iterator, stopping early if cancelled
+            for query in work {
+                if cancelled.load(Ordering::Acquire) {
+                    break;
+                }
+
+                // note that this blocks if the buffer is full
+                queries.put(Some(query));
+            }
+
+            // tell all the workers there's no more work left
+            for _ in 0..num_workers {
+                queries.put(None);
+            }
+        }));
+    }
+
+    ParMap {
+        results,
+        cancelled,
+        num_workers,
+        num_ended: 0,
+        threads,
+    }
+}
+
+/// An iterator returned by [`ordered_par_map`](fn.ordered_par_map.html), yielding results in the
+/// same order `work` yielded the corresponding items, buffering out-of-order results the same
+/// way [`ordered_pipeline`](fn.ordered_pipeline.html) does.
+///
+/// Dropping the iterator before it's exhausted cancels the run the same way
+/// [`ParMap`](struct.ParMap.html) does.
+pub struct OrderedParMap<R> {
+    results: Arc<MsQueue<Option<(usize, R)>>>,
+    cancelled: Arc<AtomicBool>,
+    next_to_emit: Arc<Watermark>,
+    num_workers: usize,
+    num_ended: usize,
+    next: usize,
+    reorder_buffer: BTreeMap<usize, R>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<R> Iterator for OrderedParMap<R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        loop {
+            if let Some(result) = self.reorder_buffer.remove(&self.next) {
+                self.next += 1;
+                self.next_to_emit.advance_to(self.next);
+                return Some(result);
+            }
+
+            if self.num_ended >= self.num_workers {
+                return None;
+            }
+
+            match self.results.pop() {
+                Some((seq, result)) => {
+                    if seq == self.next {
+                        self.next += 1;
+                        self.next_to_emit.advance_to(self.next);
+                        return Some(result);
+                    } else {
+                        self.reorder_buffer.insert(seq, result);
+                    }
+                }
+                None => self.num_ended += 1,
+            }
+        }
+    }
+}
+
+impl<R> Drop for OrderedParMap<R> {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+        // wake a feeder that might be blocked waiting for the consumer to catch up
+        self.next_to_emit.notify();
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Like [`par_map`](fn.par_map.html), but pairs it with the same input-order guarantee
+/// [`ordered_pipeline`](fn.ordered_pipeline.html) gives: results come out of the iterator in the
+/// same order `work` yielded the corresponding items, not worker-completion order, without the
+/// caller having to collect everything into a `BTreeMap` first.
+///
+/// ```
+/// use cue::ordered_par_map;
+///
+/// let results: Vec<_> = ordered_par_map("orderedparmap123", 4, 0..1_000, |n| n * 5).collect();
+///
+/// for i in 0..1_000 {
+///     assert_eq!(i * 5, results[i]);
+/// }
+/// ```
+pub fn ordered_par_map<Q, R, QF, W>(name: &str,
+                                    num_workers: usize,
+                                    work: W,
+                                    worker: QF)
+                                    -> OrderedParMap<R>
+    where Q: Send + 'static,
+          R: Send + 'static,
+          QF: Fn(Q) -> R + Sync + Send + 'static,
+          W: Iterator<Item = Q> + Send + 'static
+{
+    let name = name.to_owned();
+    let results = Arc::new(MsQueue::<Option<(usize, R)>>::new());
+    let queries = Arc::new(LinkedQueue::<Option<(usize, Q)>>::with_capacity(num_workers * 20));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let next_to_emit = Arc::new(Watermark::new());
+    let num_processed = Arc::new(AtomicUsize::new(0));
+    let window = num_workers * 20;
+    let worker = Arc::new(worker);
+
+    let mut threads = Vec::with_capacity(num_workers + 1);
+
+    // workers
+    for _ in 0..num_workers {
+        let queries = queries.clone();
+        let results = results.clone();
+        let cancelled = cancelled.clone();
+        let num_processed = num_processed.clone();
+        let worker = worker.clone();
+        let name = name.clone();
+
+        threads.push(thread::spawn(move || {
+            // while there's work to be done
+            while let Some((seq, query)) = queries.take() {
+                // once cancelled, drain and drop the rest so the feeder can finish
+                if cancelled.load(Ordering::Acquire) {
+                    continue;
+                }
+
+                let result = worker(query);
+                results.push(Some((seq, result)));
+
+                log(&name, num_processed.fetch_add(1, Ordering::Relaxed) + 1);
+            }
+            // no more work, signal to the iterator that i'm exiting
+            results.push(None);
+        }));
+    }
+
+    // feeder
+    {
+        let queries = queries.clone();
+        let cancelled = cancelled.clone();
+        let next_to_emit = next_to_emit.clone();
+
+        threads.push(thread::spawn(move || {
+            // put work on the queue from the iterator, tagging it with a sequence number
+            for (seq, query) in work.enumerate() {
+                if cancelled.load(Ordering::Acquire) {
+                    break;
+                }
+
+                // bound how far ahead of the consumer the feeder is allowed to get, blocking
+                // instead of busy-spinning until the consumer has caught up or the run is
+                // cancelled
+                next_to_emit.wait_until_within_or_cancelled(seq, window, &cancelled);
+                if cancelled.load(Ordering::Acquire) {
+                    break;
+                }
+
+                queries.put(Some((seq, query)));
+            }
+
+            // tell all the workers there's no more work left
+            for _ in 0..num_workers {
+                queries.put(None);
+            }
+        }));
+    }
+
+    OrderedParMap {
+        results,
+        cancelled,
+        next_to_emit,
+        num_workers,
+        num_ended: 0,
+        next: 0,
+        reorder_buffer: BTreeMap::new(),
+        threads,
+    }
+}
+
 #[cfg(feature="log")]
 fn log(name: &str, num_done: usize) {
     if num_done % 10_000 == 0 {
@@ -126,4 +1124,152 @@ mod tests {
             assert!(Some(&(i * 5)) == results.get(&i));
         }
     }
+
+    #[test]
+    fn ordered_test() {
+        use super::ordered_pipeline;
+
+        let mut results = Vec::new();
+
+        ordered_pipeline("ordered123", 4, 0..100_000, |n| n * 5, |r| {
+            results.push(r);
+        });
+
+        for (i, result) in results.iter().enumerate().take(100_000) {
+            assert_eq!(i * 5, *result);
+        }
+    }
+
+    #[test]
+    fn ordered_pipeline_with_capacity_respects_custom_window() {
+        use super::ordered_pipeline_with_capacity;
+
+        let mut results = Vec::new();
+
+        ordered_pipeline_with_capacity("ordered456", 4, 8, 0..100_000, |n| n * 5, |r| {
+            results.push(r);
+        });
+
+        for (i, result) in results.iter().enumerate().take(100_000) {
+            assert_eq!(i * 5, *result);
+        }
+    }
+
+    #[test]
+    fn try_pipeline_collects_all_errors() {
+        use super::{try_pipeline, FailureMode};
+
+        let mut results = Vec::new();
+
+        let outcome = try_pipeline("fallible123",
+                                    4,
+                                    0..100,
+                                    |n| if n % 10 == 0 { Err(n) } else { Ok(n) },
+                                    |r| results.push(r),
+                                    FailureMode::Collect);
+
+        let mut errors = outcome.unwrap_err();
+        errors.sort();
+
+        assert_eq!((0..100).filter(|n| n % 10 == 0).collect::<Vec<_>>(), errors);
+        assert_eq!(90, results.len());
+    }
+
+    #[test]
+    fn try_pipeline_succeeds_when_no_worker_errors() {
+        use super::{try_pipeline, FailureMode};
+
+        let mut results = Vec::new();
+
+        let outcome = try_pipeline("fallible456",
+                                    4,
+                                    0..100,
+                                    |n| Ok::<_, ()>(n * 2),
+                                    |r| results.push(r),
+                                    FailureMode::Collect);
+
+        assert_eq!(Ok(()), outcome);
+        assert_eq!(100, results.len());
+    }
+
+    #[test]
+    fn pipeline_config_auto_runs() {
+        use std::collections::BTreeMap;
+        use super::PipelineConfig;
+
+        let mut results = BTreeMap::new();
+
+        PipelineConfig::auto()
+            .queue_capacity(64)
+            .output_capacity(64)
+            .run("auto123", 0..10_000, |n| (n, n * 5), |r| {
+                results.insert(r.0, r.1);
+            });
+
+        for i in 0..100 {
+            assert!(Some(&(i * 5)) == results.get(&i));
+        }
+    }
+
+    #[test]
+    fn pipeline_config_adaptive_runs() {
+        use std::collections::BTreeMap;
+        use super::PipelineConfig;
+
+        let mut results = BTreeMap::new();
+
+        PipelineConfig::new(4)
+            .adaptive(10, 200)
+            .run("adaptive123", 0..10_000, |n| (n, n * 5), |r| {
+                results.insert(r.0, r.1);
+            });
+
+        for i in 0..100 {
+            assert!(Some(&(i * 5)) == results.get(&i));
+        }
+    }
+
+    #[test]
+    fn par_map_yields_every_result() {
+        use super::par_map;
+
+        let mut results: Vec<_> = par_map("parmap123", 4, 0..10_000, |n| n * 5).collect();
+        results.sort();
+
+        for (i, result) in results.iter().enumerate().take(10_000) {
+            assert_eq!(i * 5, *result);
+        }
+    }
+
+    #[test]
+    fn ordered_par_map_preserves_input_order() {
+        use super::ordered_par_map;
+
+        let results: Vec<_> = ordered_par_map("orderedparmap123", 4, 0..10_000, |n| n * 5)
+            .collect();
+
+        for (i, result) in results.iter().enumerate().take(10_000) {
+            assert_eq!(i * 5, *result);
+        }
+    }
+
+    #[test]
+    fn pipeline_config_jobserver_falls_back_without_one() {
+        use std::collections::BTreeMap;
+        use super::PipelineConfig;
+
+        let mut results = BTreeMap::new();
+
+        // no jobserver is present in the test environment, so this should behave just like a
+        // plain `PipelineConfig::new(4).run(...)`
+        PipelineConfig::new(4)
+            .jobserver()
+            .run("jobserver123", 0..1_000, |n| (n, n * 5), |r| {
+                results.insert(r.0, r.1);
+            });
+
+        for i in 0..100 {
+            assert!(Some(&(i * 5)) == results.get(&i));
+        }
+    }
 }